@@ -4,7 +4,13 @@ use rspotify::{
     model::TrackId,
     prelude::{BaseClient, OAuthClient},
 };
-use std::{env, error::Error, ops::Not};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    ops::Not,
+    sync::Arc,
+};
 use teloxide::{
     dispatching::{
         dialogue::{self, GetChatId, InMemStorage},
@@ -15,15 +21,18 @@ use teloxide::{
     requests::JsonRequest,
     types::{
         InlineKeyboardButton, InlineKeyboardMarkup, LinkPreviewOptions, MaybeInaccessibleMessage,
-        MessageId, ParseMode, ReplyParameters, ThreadId, User,
+        MessageId, ParseMode, ReplyParameters, ThreadId, User, UserId,
     },
     utils::command::BotCommands,
 };
+use tokio::sync::Mutex;
 
 type MyDialogue = Dialogue<State, InMemStorage<State>>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+type VoteStore = Arc<Mutex<HashMap<MessageId, VoteState>>>;
 
 use tg_music_bot::spotify::*;
+use tg_music_bot::stats::*;
 
 #[tokio::main]
 async fn main() {
@@ -46,9 +55,14 @@ async fn main() {
         }),
         Err(_) => None,
     };
+    let required_votes = env::var("REQUIRED_VOTES")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(1);
     let parameters = ConfigParameters {
         voting_chat,
         voting_thread,
+        required_votes,
     };
 
     let bot = Bot::from_env();
@@ -57,7 +71,9 @@ async fn main() {
         .dependencies(dptree::deps![
             InMemStorage::<State>::new(),
             setup_spotify().await,
-            parameters
+            parameters,
+            VoteStore::default(),
+            Stats::from_env()
         ])
         .enable_ctrlc_handler()
         .build()
@@ -79,7 +95,9 @@ async fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'st
     );
     let command_handler = teloxide::filter_command::<Command, _>()
         .branch(case![Command::Help].endpoint(help))
-        .branch(case![Command::Id].endpoint(id));
+        .branch(case![Command::Id].endpoint(id))
+        .branch(case![Command::NowPlaying].endpoint(now_playing))
+        .branch(case![Command::Stats].endpoint(show_stats));
 
     let callback_handler = Update::filter_callback_query().branch(
         dptree::filter(|cfg: ConfigParameters, q: CallbackQuery| {
@@ -117,9 +135,14 @@ async fn spotify_login(
 ) -> HandlerResult {
     match spotify.get_authorize_url(false) {
         Ok(auth_url) => {
+            let chat_id = msg.chat.id;
             let send_msg = bot.send_message(msg.chat.id, format!("Spotify Login\nOpen this URL in the browser and allow Spotify access: {}\nThen paste and send the redirected URL here.", auth_url));
             set_reply(msg, send_msg).await?;
             dialogue.update(State::SpotifyLogin).await?;
+            // Also try to catch the redirect ourselves so the admin doesn't have to
+            // paste the URL back; if that isn't reachable the paste-based flow above
+            // still completes the login once `spotify_login_token` sees the message.
+            spawn_local_login_listener(bot, dialogue, spotify, chat_id);
         }
         Err(e) => {
             let send_msg: JsonRequest<SendMessage> =
@@ -131,6 +154,30 @@ async fn spotify_login(
     Ok(())
 }
 
+fn spawn_local_login_listener(
+    bot: Bot,
+    dialogue: MyDialogue,
+    spotify: rspotify::AuthCodeSpotify,
+    chat_id: ChatId,
+) {
+    tokio::spawn(async move {
+        match complete_login_via_local_server(&spotify).await {
+            Ok(()) => {
+                dialogue.update(State::Start).await.ok();
+                let _ = bot
+                    .send_message(
+                        chat_id,
+                        "Token saved (captured automatically from the redirect).",
+                    )
+                    .await;
+            }
+            Err(err) => {
+                warn!("Local Spotify OAuth listener did not complete: {}", err);
+            }
+        }
+    });
+}
+
 async fn spotify_login_token(
     bot: Bot,
     dialogue: MyDialogue,
@@ -142,7 +189,7 @@ async fn spotify_login_token(
         .and_then(|text| spotify.parse_response_code(text))
     {
         Some(code) => {
-            spotify.request_token(&code).await?;
+            with_retry(|| spotify.request_token(&code)).await?;
             spotify.write_token_cache().await?;
             let send_msg: JsonRequest<SendMessage> = bot.send_message(msg.chat.id, "Token saved");
             set_reply(msg, send_msg).await?;
@@ -162,10 +209,18 @@ async fn request_track(
     cfg: ConfigParameters,
     msg: Message,
     spotify: rspotify::AuthCodeSpotify,
+    votes: VoteStore,
+    stats: Stats,
 ) -> HandlerResult {
     let requester = format_author(msg.from.as_ref());
+    let text = msg.text().unwrap().to_string();
+
+    if let Some(collection) = SpotifyCollectionId::from_url(text.clone()).await {
+        return request_collection(bot, cfg, msg, spotify, votes, stats, collection, requester)
+            .await;
+    }
 
-    match fetch_track(&spotify, msg.text().unwrap().into()).await {
+    match fetch_track(&spotify, text).await {
         Ok(track) => {
             let id = track.id.as_ref().unwrap().to_string();
             let track_text = format_track_text(&track);
@@ -179,21 +234,11 @@ async fn request_track(
             .link_preview_options(preview.clone())
             .await?;
 
+            stats.record_request(&requester, &id).await;
+
             // inform voting chat
-            let buttons = vec![vec![
-                InlineKeyboardButton::new(
-                    "✅ Add to queue".to_string(),
-                    teloxide::types::InlineKeyboardButtonKind::CallbackData(format!(
-                        "accept:{}",
-                        id
-                    )),
-                ),
-                InlineKeyboardButton::new(
-                    "❌ Decline".to_string(),
-                    teloxide::types::InlineKeyboardButtonKind::CallbackData("decline".to_string()),
-                ),
-            ]];
-            let keyboard = InlineKeyboardMarkup::new(buttons);
+            let target = VoteTarget::Track(id);
+            let keyboard = vote_keyboard(&target, 0, 0, cfg.required_votes);
 
             let mut voting_msg = bot
                 .send_message(
@@ -206,7 +251,11 @@ async fn request_track(
             if let Some(thread) = cfg.voting_thread {
                 voting_msg = voting_msg.message_thread_id(thread);
             }
-            voting_msg.await?;
+            let sent = voting_msg.await?;
+            votes
+                .lock()
+                .await
+                .insert(sent.id, VoteState::new(target, requester));
             Ok(())
         }
         Err(e) => {
@@ -217,67 +266,250 @@ async fn request_track(
     }
 }
 
+async fn request_collection(
+    bot: Bot,
+    cfg: ConfigParameters,
+    msg: Message,
+    spotify: rspotify::AuthCodeSpotify,
+    votes: VoteStore,
+    stats: Stats,
+    collection: SpotifyCollectionId,
+    requester: String,
+) -> HandlerResult {
+    match fetch_collection(&spotify, &collection).await {
+        Ok(resolved) => {
+            let summary = format!(
+                "💿 <b>{}</b>\n🎵 {} tracks • ⏱️ {}",
+                resolved.name,
+                resolved.track_ids.len(),
+                format_duration(resolved.total_duration)
+            );
+            // inform requester
+            bot.send_message(
+                msg.chat.id,
+                format!("✅ Successfully requested collection.\n\n{}", summary),
+            )
+            .parse_mode(ParseMode::Html)
+            .await?;
+
+            stats
+                .record_request(&requester, &collection.callback_data())
+                .await;
+
+            // inform voting chat
+            let target = VoteTarget::Collection(collection);
+            let keyboard = vote_keyboard(&target, 0, 0, cfg.required_votes);
+
+            let mut voting_msg = bot
+                .send_message(
+                    cfg.voting_chat,
+                    format!("User {} requested:\n{}", requester, summary),
+                )
+                .parse_mode(ParseMode::Html)
+                .reply_markup(keyboard);
+            if let Some(thread) = cfg.voting_thread {
+                voting_msg = voting_msg.message_thread_id(thread);
+            }
+            let sent = voting_msg.await?;
+            votes
+                .lock()
+                .await
+                .insert(sent.id, VoteState::new(target, requester));
+            Ok(())
+        }
+        Err(e) => {
+            bot.send_message(msg.chat.id, "❌ Failed to find album or playlist.")
+                .await?;
+            HandlerResult::Err(format!("Failed to fetch a collection {:?}", e).into())
+        }
+    }
+}
+
 async fn handle_callback(
     bot: Bot,
     q: CallbackQuery,
     spotify: rspotify::AuthCodeSpotify,
+    cfg: ConfigParameters,
+    votes: VoteStore,
+    stats: Stats,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let msg = match q.message {
-        Some(MaybeInaccessibleMessage::Regular(ref message)) => Some(message),
-        _ => None,
+        Some(MaybeInaccessibleMessage::Regular(ref message)) => message,
+        _ => return Ok(()),
     };
-    let mut disable_preview = false;
-    let reply = match q.data.as_deref() {
-        Some(accept) if accept.starts_with("accept:") => {
-            match SpotifyTrackId::from_urn(accept.into()) {
-                Some(track) => match TrackId::from_id(&track.track_id) {
-                    Ok(trackid) => match spotify.add_item_to_queue(trackid.into(), None).await {
-                        Ok(_) => {
-                            format!(
-                                "✅ {} has added to queue:\n{}",
-                                format_author(Some(&q.from)),
-                                msg.and_then(|m| m.text()).unwrap_or(&track.track_url())
-                            )
-                        }
+
+    let is_accept = q.data.as_deref().is_some_and(|d| d.starts_with("accept"));
+    let is_decline = q.data.as_deref() == Some("decline");
+    if !is_accept && !is_decline {
+        return Ok(());
+    }
+
+    let mut store = votes.lock().await;
+    if !store.contains_key(&msg.id) {
+        // The bot restarted since this message was sent; rebuild its vote state
+        // from the accept button's callback data, starting from empty tallies.
+        // A "decline" press carries no target, so it is ignored until an accept
+        // press has rebuilt the state. The original requester is lost across
+        // restarts, so stats attribute the outcome to "unknown".
+        let target = match q.data.as_deref().and_then(vote_target_from_callback_data) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        store.insert(msg.id, VoteState::new(target, "unknown".to_string()));
+    }
+    let state = store.get_mut(&msg.id).expect("just inserted if missing");
+    if state.decided {
+        // The vote already concluded (or is concluding in a race with this
+        // press) — ignore it so a stale keyboard can't replay the outcome.
+        return Ok(());
+    }
+
+    // A repeat press of the same button a user already cast is a no-op: the
+    // sets don't change, so skip straight to returning instead of re-editing
+    // an unchanged keyboard (Telegram 400s on "message is not modified").
+    let changed = if is_accept {
+        let removed_decline = state.declined_by.remove(&q.from.id);
+        let inserted_accept = state.accepted_by.insert(q.from.id);
+        removed_decline || inserted_accept
+    } else {
+        let removed_accept = state.accepted_by.remove(&q.from.id);
+        let inserted_decline = state.declined_by.insert(q.from.id);
+        removed_accept || inserted_decline
+    };
+    if !changed {
+        return Ok(());
+    }
+
+    let accepted = state.accepted_by.len();
+    let declined = state.declined_by.len();
+    let required = cfg.required_votes as usize;
+    let target = state.target.clone();
+    let requester = state.requester.clone();
+    let accepted_by = vote_ids_to_strings(&state.accepted_by);
+    let declined_by = vote_ids_to_strings(&state.declined_by);
+
+    if accepted >= required {
+        state.decided = true;
+        drop(store);
+        stats
+            .record_outcome(&requester, Outcome::Accepted, &accepted_by, &declined_by)
+            .await;
+        let reply = accept_target(&spotify, &q, msg, target).await;
+        finalize_message(&bot, msg, reply, false).await?;
+    } else if declined >= required {
+        state.decided = true;
+        drop(store);
+        stats
+            .record_outcome(&requester, Outcome::Declined, &accepted_by, &declined_by)
+            .await;
+        let author = format_author(Some(&q.from));
+        let reply = match msg.text() {
+            Some(text) => format!("❌ {} hat abgelehnt: {}", author, text),
+            None => format!("❌ {} hat abgelehnt.", author),
+        };
+        finalize_message(&bot, msg, reply, true).await?;
+    } else {
+        let keyboard = vote_keyboard(&target, accepted, declined, cfg.required_votes);
+        drop(store);
+        bot.edit_message_reply_markup(msg.chat.id, msg.id)
+            .reply_markup(keyboard)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Runs the queueing action for an accepted vote target, returning the text to
+/// replace the voting message with.
+async fn accept_target(
+    spotify: &rspotify::AuthCodeSpotify,
+    q: &CallbackQuery,
+    msg: &Message,
+    target: VoteTarget,
+) -> String {
+    let author = format_author(Some(&q.from));
+    match target {
+        VoteTarget::Track(track_urn) => match SpotifyTrackId::from_urn(track_urn) {
+            Some(track) => match TrackId::from_id(&track.track_id) {
+                Ok(trackid) => {
+                    match with_retry(|| spotify.add_item_to_queue(trackid.clone().into(), None))
+                        .await
+                    {
+                        Ok(_) => format!(
+                            "✅ {} has added to queue:\n{}",
+                            author,
+                            msg.text().unwrap_or(&track.track_url())
+                        ),
                         Err(err) => {
                             warn!("Failed to queue track {err}");
                             format!("Failed to queue track: {}", err)
                         }
-                    },
-                    Err(_) => "Invalid track ID".into(),
-                },
-                None => "Invalid track ID".into(),
-            }
-        }
-        Some("decline") => {
-            disable_preview = true;
-            let author = format_author(Some(&q.from));
-            match msg.and_then(|msg| msg.text()) {
-                Some(text) => format!("❌ {} hat abgelehnt: {}", author, text),
-                None => format!("❌ {} hat abgelehnt.", author),
+                    }
+                }
+                Err(_) => "Invalid track ID".into(),
+            },
+            None => "Invalid track ID".into(),
+        },
+        VoteTarget::Collection(collection) => match fetch_collection(spotify, &collection).await {
+            Ok(resolved) => {
+                let mut queued = 0usize;
+                let mut failed = 0usize;
+                for track_id in resolved.track_ids {
+                    match with_retry(|| spotify.add_item_to_queue(track_id.clone().into(), None))
+                        .await
+                    {
+                        Ok(_) => queued += 1,
+                        Err(err) => {
+                            warn!("Failed to queue track {err}");
+                            failed += 1;
+                        }
+                    }
+                }
+                format!(
+                    "✅ {} has added {} of {} tracks from \"{}\" to queue.",
+                    author,
+                    queued,
+                    queued + failed,
+                    resolved.name
+                )
             }
-        }
-        _ => return Ok(()),
-    };
-    // edit existing message with status or send a new message
-    if let Some(msg) = msg {
-        let mut update = bot
-            .edit_message_text(msg.chat.id, msg.id, reply)
-            .parse_mode(ParseMode::Html)
-            .reply_markup(InlineKeyboardMarkup::default());
-        if disable_preview {
-            update = update.link_preview_options(teloxide::types::LinkPreviewOptions {
-                is_disabled: true,
-                url: None,
-                prefer_small_media: false,
-                prefer_large_media: false,
-                show_above_text: false,
-            });
-        }
-        update.await?;
-    } else if let Some(chat_id) = q.chat_id() {
-        bot.send_message(chat_id, reply).await?;
+            Err(err) => format!("Failed to queue collection: {}", err),
+        },
     }
+}
+
+fn vote_target_from_callback_data(data: &str) -> Option<VoteTarget> {
+    if let Some(collection) = SpotifyCollectionId::from_callback_data(data) {
+        return Some(VoteTarget::Collection(collection));
+    }
+    SpotifyTrackId::from_urn(data.to_string()).map(|track| VoteTarget::Track(track.track_urn()))
+}
+
+/// Renders a set of voter ids as strings for the stats backend.
+fn vote_ids_to_strings(ids: &HashSet<UserId>) -> Vec<String> {
+    ids.iter().map(|id| id.0.to_string()).collect()
+}
+
+/// Replaces the voting message's text with the final outcome and removes its keyboard.
+async fn finalize_message(
+    bot: &Bot,
+    msg: &Message,
+    reply: String,
+    disable_preview: bool,
+) -> HandlerResult {
+    let mut update = bot
+        .edit_message_text(msg.chat.id, msg.id, reply)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(InlineKeyboardMarkup::default());
+    if disable_preview {
+        update = update.link_preview_options(teloxide::types::LinkPreviewOptions {
+            is_disabled: true,
+            url: None,
+            prefer_small_media: false,
+            prefer_large_media: false,
+            show_above_text: false,
+        });
+    }
+    update.await?;
     Ok(())
 }
 
@@ -302,6 +534,50 @@ async fn id(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
+async fn now_playing(
+    bot: Bot,
+    msg: Message,
+    spotify: rspotify::AuthCodeSpotify,
+) -> HandlerResult {
+    match fetch_now_playing(&spotify).await {
+        Ok(Some(now_playing)) => {
+            let text = format_now_playing_text(&now_playing);
+            let preview = link_preview_for_url(
+                now_playing.track.album.images.first().map(|i| i.url.clone()),
+            );
+            bot.send_message(msg.chat.id, text)
+                .parse_mode(ParseMode::Html)
+                .link_preview_options(preview)
+                .await?;
+        }
+        Ok(None) => {
+            bot.send_message(msg.chat.id, "Nothing is playing right now.")
+                .await?;
+        }
+        Err(e) => {
+            warn!("Failed to fetch now playing: {e}");
+            bot.send_message(msg.chat.id, "❌ Failed to fetch current playback.")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn show_stats(bot: Bot, msg: Message, stats: Stats) -> HandlerResult {
+    match stats.summary().await {
+        Some(summary) => {
+            bot.send_message(msg.chat.id, format_summary(&summary))
+                .parse_mode(ParseMode::Html)
+                .await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, "Stats aren't enabled on this bot.")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 fn set_reply(msg: Message, send_msg: JsonRequest<SendMessage>) -> JsonRequest<SendMessage> {
     if let Some(thread) = msg.thread_id {
         send_msg.reply_parameters(ReplyParameters::new(thread.0))
@@ -351,6 +627,66 @@ pub enum State {
 struct ConfigParameters {
     voting_chat: ChatId,
     voting_thread: Option<ThreadId>,
+    required_votes: u32,
+}
+
+/// What a voting message's "Add to queue" button resolves to once accepted.
+#[derive(Clone)]
+enum VoteTarget {
+    Track(String),
+    Collection(SpotifyCollectionId),
+}
+
+impl VoteTarget {
+    fn accept_callback_data(&self) -> String {
+        match self {
+            VoteTarget::Track(track_id) => format!("accept:{}", track_id),
+            VoteTarget::Collection(collection) => collection.callback_data(),
+        }
+    }
+}
+
+/// Per-message tally of distinct voters, keyed by the voting message's `MessageId`.
+struct VoteState {
+    target: VoteTarget,
+    requester: String,
+    accepted_by: HashSet<UserId>,
+    declined_by: HashSet<UserId>,
+    /// Set once the vote has been accepted or declined, so a stale/duplicate
+    /// button press on an already-finalized message can't replay the outcome.
+    decided: bool,
+}
+
+impl VoteState {
+    fn new(target: VoteTarget, requester: String) -> Self {
+        Self {
+            target,
+            requester,
+            accepted_by: HashSet::new(),
+            declined_by: HashSet::new(),
+            decided: false,
+        }
+    }
+}
+
+fn vote_keyboard(
+    target: &VoteTarget,
+    accepted: usize,
+    declined: usize,
+    required: u32,
+) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::new(
+            format!("✅ {}/{}", accepted, required),
+            teloxide::types::InlineKeyboardButtonKind::CallbackData(
+                target.accept_callback_data(),
+            ),
+        ),
+        InlineKeyboardButton::new(
+            format!("❌ {}", declined),
+            teloxide::types::InlineKeyboardButtonKind::CallbackData("decline".to_string()),
+        ),
+    ]])
 }
 
 #[derive(BotCommands, Clone)]
@@ -365,4 +701,8 @@ enum Command {
     SpotifyLogin,
     #[command(description = "get chat/thread id")]
     Id,
+    #[command(description = "show what's currently playing")]
+    NowPlaying,
+    #[command(description = "show request/voting stats")]
+    Stats,
 }
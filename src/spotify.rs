@@ -3,15 +3,85 @@ use std::{
     error::Error,
     fmt::{self, Debug},
     path::PathBuf,
+    time::Duration,
 };
 
+use log::warn;
 use regex::Regex;
-use reqwest::redirect;
+use reqwest::{header::RETRY_AFTER, redirect, StatusCode};
 use rspotify::{
-    model::{Country, FullTrack, Market, TrackId},
+    http::HttpError,
+    model::{AlbumId, Country, FullTrack, Market, PlayableItem, PlaylistId, TrackId},
     prelude::{BaseClient, OAuthClient},
-    AuthCodeSpotify,
+    AuthCodeSpotify, ClientError,
 };
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+
+/// Page size used when paginating through album/playlist tracks.
+const COLLECTION_PAGE_SIZE: u32 = 50;
+
+/// How long the local OAuth callback server waits for Spotify's redirect before
+/// giving up and leaving the paste-based login flow as the only option.
+const LOGIN_SERVER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Maximum number of attempts `with_retry` makes before giving up and
+/// surfacing the underlying error.
+const MAX_RETRY_ATTEMPTS: u32 = 5;
+/// Fallback delay when Spotify returns a 429 without a `Retry-After` value.
+const DEFAULT_RETRY_AFTER_SECS: u64 = 5;
+
+/// Runs `op`, transparently retrying on Spotify rate limiting.
+///
+/// rspotify's reqwest backend surfaces a 429 response as
+/// `ClientError::Http(HttpError::StatusCode(response))`, with the raw response (and
+/// thus the `Retry-After` header) still attached, so we parse that header ourselves.
+/// On that error we sleep for the requested duration (or [`DEFAULT_RETRY_AFTER_SECS`]
+/// if the header was missing or unparseable) and try again, up to
+/// [`MAX_RETRY_ATTEMPTS`] times. Any other error is returned immediately without retrying.
+pub async fn with_retry<F, Fut, T>(mut op: F) -> Result<T, ClientError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, ClientError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                let retry_after = match &err {
+                    ClientError::Http(HttpError::StatusCode(response))
+                        if response.status() == StatusCode::TOO_MANY_REQUESTS =>
+                    {
+                        Some(
+                            response
+                                .headers()
+                                .get(RETRY_AFTER)
+                                .and_then(|header| header.to_str().ok())
+                                .and_then(|header| header.parse::<u64>().ok())
+                                .unwrap_or(DEFAULT_RETRY_AFTER_SECS),
+                        )
+                    }
+                    _ => None,
+                };
+                match retry_after {
+                    Some(seconds) if attempt + 1 < MAX_RETRY_ATTEMPTS => {
+                        attempt += 1;
+                        warn!(
+                            "Spotify rate limited, retrying in {}s (attempt {}/{})",
+                            seconds, attempt, MAX_RETRY_ATTEMPTS
+                        );
+                        sleep(Duration::from_secs(seconds)).await;
+                    }
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+}
 
 pub async fn setup_spotify() -> AuthCodeSpotify {
     use rspotify::{scopes, AuthCodeSpotify, Credentials, OAuth};
@@ -55,6 +125,104 @@ pub async fn setup_spotify() -> AuthCodeSpotify {
     spotify
 }
 
+#[derive(Debug)]
+pub enum LocalLoginError {
+    Bind(std::io::Error),
+    Timeout,
+    MissingCode,
+    SpotifyApiError(rspotify::ClientError),
+}
+
+impl fmt::Display for LocalLoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalLoginError::Bind(err) => {
+                write!(f, "Could not bind local OAuth callback server: {}", err)
+            }
+            LocalLoginError::Timeout => write!(f, "Timed out waiting for Spotify's redirect"),
+            LocalLoginError::MissingCode => {
+                write!(f, "Redirect did not contain an authorization code")
+            }
+            LocalLoginError::SpotifyApiError(err) => write!(f, "Spotify API error: {}", err),
+        }
+    }
+}
+
+impl Error for LocalLoginError {}
+
+impl From<rspotify::ClientError> for LocalLoginError {
+    fn from(err: rspotify::ClientError) -> Self {
+        LocalLoginError::SpotifyApiError(err)
+    }
+}
+
+/// Splits a redirect URI like `http://localhost:8888/callback` into the `host:port`
+/// to bind to and the path the redirect is expected on.
+fn parse_redirect_uri(redirect_uri: &str) -> (String, String) {
+    let without_scheme = redirect_uri
+        .split_once("://")
+        .map_or(redirect_uri, |(_, rest)| rest);
+    match without_scheme.split_once('/') {
+        Some((host, path)) => (host.to_string(), format!("/{}", path)),
+        None => (without_scheme.to_string(), "/".to_string()),
+    }
+}
+
+/// Extracts a single query parameter's value from a request target like `/callback?code=abc`.
+fn extract_query_param(path_and_query: &str, key: &str) -> Option<String> {
+    let (_, query) = path_and_query.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=')?;
+        (k == key).then(|| v.to_string())
+    })
+}
+
+/// Completes the Spotify OAuth authorization-code flow by briefly listening on the
+/// configured `redirect_uri` for the browser redirect, instead of requiring the admin
+/// to paste the redirected URL back into Telegram. Binds to the redirect URI's host
+/// and port, waits for a single request carrying a `code` query parameter (or times
+/// out after [`LOGIN_SERVER_TIMEOUT`]), exchanges it for a token and caches it.
+pub async fn complete_login_via_local_server(
+    spotify: &AuthCodeSpotify,
+) -> Result<(), LocalLoginError> {
+    let (bind_addr, callback_path) = parse_redirect_uri(&spotify.oauth.redirect_uri);
+
+    let listener = TcpListener::bind(&bind_addr)
+        .await
+        .map_err(LocalLoginError::Bind)?;
+    log::info!("Waiting for Spotify OAuth redirect on {}{}", bind_addr, callback_path);
+
+    let (mut socket, _) = timeout(LOGIN_SERVER_TIMEOUT, listener.accept())
+        .await
+        .map_err(|_| LocalLoginError::Timeout)?
+        .map_err(LocalLoginError::Bind)?;
+
+    let mut buf = [0u8; 4096];
+    let n = socket.read(&mut buf).await.map_err(LocalLoginError::Bind)?;
+    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+    let path_and_query = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or_default();
+
+    let code = extract_query_param(path_and_query, "code").ok_or(LocalLoginError::MissingCode)?;
+
+    let body = "Spotify login complete, you can close this tab and return to Telegram.";
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    // Best-effort: the browser already has what it needs even if this write fails.
+    let _ = socket.write_all(response.as_bytes()).await;
+
+    with_retry(|| spotify.request_token(&code)).await?;
+    spotify.write_token_cache().await?;
+
+    Ok(())
+}
+
 pub struct SpotifyTrackId {
     pub track_id: String,
 }
@@ -88,7 +256,6 @@ impl SpotifyTrackId {
                 .map(|id| Self { track_id: id })
         })
     }
-    #[allow(dead_code)]
     pub fn track_urn(&self) -> String {
         format!("spotify:track:{}", self.track_id)
     }
@@ -112,10 +279,86 @@ impl SpotifyTrackId {
     }
 }
 
+/// A Spotify album or playlist link, as opposed to a single [`SpotifyTrackId`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpotifyCollectionKind {
+    Album,
+    Playlist,
+}
+
+#[derive(Clone)]
+pub struct SpotifyCollectionId {
+    pub kind: SpotifyCollectionKind,
+    pub collection_id: String,
+}
+
+impl SpotifyCollectionId {
+    pub async fn from_url(url: String) -> Option<Self> {
+        let re_link = Regex::new(r"https?://spotify\.link/(\w+)").unwrap();
+        let resolved = if re_link.is_match(&url) {
+            SpotifyTrackId::resolve_spotify_link(&url).await.unwrap_or(url)
+        } else {
+            url
+        };
+
+        let re_album =
+            Regex::new(r"(?:open\.spotify\.com/album/|spotify:album:)(\w+)").unwrap();
+        if let Some(c) = re_album.captures(&resolved) {
+            return Some(Self {
+                kind: SpotifyCollectionKind::Album,
+                collection_id: c[1].to_string(),
+            });
+        }
+
+        let re_playlist =
+            Regex::new(r"(?:open\.spotify\.com/playlist/|spotify:playlist:)(\w+)").unwrap();
+        if let Some(c) = re_playlist.captures(&resolved) {
+            return Some(Self {
+                kind: SpotifyCollectionKind::Playlist,
+                collection_id: c[1].to_string(),
+            });
+        }
+
+        None
+    }
+
+    /// Callback data for the "Add all to queue" button, resolved again on click
+    /// since the full track list doesn't fit in Telegram's callback data limit.
+    pub fn callback_data(&self) -> String {
+        match self.kind {
+            SpotifyCollectionKind::Album => format!("accept_album:{}", self.collection_id),
+            SpotifyCollectionKind::Playlist => format!("accept_playlist:{}", self.collection_id),
+        }
+    }
+
+    pub fn from_callback_data(data: &str) -> Option<Self> {
+        if let Some(id) = data.strip_prefix("accept_album:") {
+            Some(Self {
+                kind: SpotifyCollectionKind::Album,
+                collection_id: id.to_string(),
+            })
+        } else if let Some(id) = data.strip_prefix("accept_playlist:") {
+            Some(Self {
+                kind: SpotifyCollectionKind::Playlist,
+                collection_id: id.to_string(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct ResolvedCollection {
+    pub name: String,
+    pub track_ids: Vec<TrackId<'static>>,
+    pub total_duration: chrono::Duration,
+}
+
 #[derive(Debug)]
 pub enum FetchTrackError {
     InvalidTrackUrl(String),
     InvalidTrackUri(String),
+    InvalidCollectionId(String),
     SpotifyApiError(rspotify::ClientError),
 }
 
@@ -124,6 +367,9 @@ impl fmt::Display for FetchTrackError {
         match self {
             FetchTrackError::InvalidTrackUrl(url) => write!(f, "Invalid track URL: {}", url),
             FetchTrackError::InvalidTrackUri(uri) => write!(f, "Invalid track URI: {}", uri),
+            FetchTrackError::InvalidCollectionId(id) => {
+                write!(f, "Invalid Spotify collection id: {}", id)
+            }
             FetchTrackError::SpotifyApiError(err) => write!(f, "Spotify API error: {}", err),
         }
     }
@@ -149,13 +395,190 @@ pub async fn fetch_track(
     let track_id = TrackId::from_uri(track_urn)
         .map_err(|_| FetchTrackError::InvalidTrackUri(track_id.track_urn()))?;
 
-    let track = spotify
-        .track(track_id, Some(Market::Country(Country::Germany)))
-        .await?;
+    let track = with_retry(|| {
+        spotify.track(track_id.clone(), Some(Market::Country(Country::Germany)))
+    })
+    .await?;
 
     Ok(track)
 }
 
+/// The currently playing track and where playback is at within it.
+pub struct NowPlaying {
+    pub track: FullTrack,
+    pub progress: chrono::Duration,
+    pub is_playing: bool,
+}
+
+/// Fetches the user's current playback state. Returns `Ok(None)` both when nothing
+/// is playing and when the currently playing item is an episode rather than a track.
+pub async fn fetch_now_playing(
+    spotify: &AuthCodeSpotify,
+) -> Result<Option<NowPlaying>, FetchTrackError> {
+    let playback = with_retry(|| {
+        spotify.current_playback(Some(Market::Country(Country::Germany)), None::<Vec<_>>)
+    })
+    .await?;
+
+    Ok(playback.and_then(|playback| match playback.item {
+        Some(PlayableItem::Track(track)) => Some(NowPlaying {
+            track,
+            progress: playback.progress.unwrap_or_default(),
+            is_playing: playback.is_playing,
+        }),
+        _ => None,
+    }))
+}
+
+/// Number of segments in the textual progress bar rendered by [`format_progress_bar`].
+const PROGRESS_BAR_LENGTH: usize = 10;
+
+fn format_progress_bar(progress: chrono::Duration, duration: chrono::Duration) -> String {
+    let ratio = if duration.num_milliseconds() > 0 {
+        (progress.num_milliseconds() as f64 / duration.num_milliseconds() as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let marker = ((ratio * PROGRESS_BAR_LENGTH as f64) as usize).min(PROGRESS_BAR_LENGTH - 1);
+    (0..PROGRESS_BAR_LENGTH)
+        .map(|i| if i == marker { '🔘' } else { '▬' })
+        .collect()
+}
+
+pub fn format_now_playing_text(now_playing: &NowPlaying) -> String {
+    let status = if now_playing.is_playing {
+        "▶️"
+    } else {
+        "⏸️"
+    };
+    format!(
+        "{} {}\n{}\n{} / {}",
+        status,
+        format_track_text(&now_playing.track),
+        format_progress_bar(now_playing.progress, now_playing.track.duration),
+        format_duration(now_playing.progress),
+        format_duration(now_playing.track.duration)
+    )
+}
+
+/// Pages through a Spotify listing in chunks of [`COLLECTION_PAGE_SIZE`], stopping once a
+/// page comes back empty or shorter than the page size — the same chunked loop used for
+/// any other large Spotify collection.
+async fn paginate_all<F, Fut, T>(mut fetch_page: F) -> Result<Vec<T>, rspotify::ClientError>
+where
+    F: FnMut(u32, u32) -> Fut,
+    Fut: std::future::Future<Output = Result<rspotify::model::Page<T>, rspotify::ClientError>>,
+{
+    let mut items = Vec::new();
+    let mut offset = 0u32;
+    loop {
+        let page = fetch_page(COLLECTION_PAGE_SIZE, offset).await?;
+        let len = page.items.len() as u32;
+        items.extend(page.items);
+        if len < COLLECTION_PAGE_SIZE {
+            break;
+        }
+        offset += COLLECTION_PAGE_SIZE;
+    }
+    Ok(items)
+}
+
+pub async fn fetch_collection(
+    spotify: &AuthCodeSpotify,
+    collection: &SpotifyCollectionId,
+) -> Result<ResolvedCollection, FetchTrackError> {
+    match collection.kind {
+        SpotifyCollectionKind::Album => fetch_album(spotify, &collection.collection_id).await,
+        SpotifyCollectionKind::Playlist => {
+            fetch_playlist(spotify, &collection.collection_id).await
+        }
+    }
+}
+
+async fn fetch_album(
+    spotify: &AuthCodeSpotify,
+    id: &str,
+) -> Result<ResolvedCollection, FetchTrackError> {
+    let album_id =
+        AlbumId::from_id(id).map_err(|_| FetchTrackError::InvalidCollectionId(id.to_string()))?;
+
+    let album = with_retry(|| {
+        spotify.album(album_id.clone(), Some(Market::Country(Country::Germany)))
+    })
+    .await?;
+
+    let tracks = paginate_all(|limit, offset| {
+        spotify.album_track_manual(
+            album_id.clone(),
+            Some(Market::Country(Country::Germany)),
+            Some(limit),
+            Some(offset),
+        )
+    })
+    .await?;
+
+    let mut track_ids = Vec::with_capacity(tracks.len());
+    let mut total_duration = chrono::Duration::zero();
+    for track in tracks {
+        if let Some(track_id) = track.id {
+            track_ids.push(track_id);
+            total_duration += track.duration;
+        }
+    }
+
+    Ok(ResolvedCollection {
+        name: album.name,
+        track_ids,
+        total_duration,
+    })
+}
+
+async fn fetch_playlist(
+    spotify: &AuthCodeSpotify,
+    id: &str,
+) -> Result<ResolvedCollection, FetchTrackError> {
+    let playlist_id = PlaylistId::from_id(id)
+        .map_err(|_| FetchTrackError::InvalidCollectionId(id.to_string()))?;
+
+    let playlist = with_retry(|| spotify.playlist(playlist_id.clone(), None, None)).await?;
+
+    let items = paginate_all(|limit, offset| {
+        spotify.playlist_items_manual(
+            playlist_id.clone(),
+            None,
+            Some(Market::Country(Country::Germany)),
+            Some(limit),
+            Some(offset),
+        )
+    })
+    .await?;
+
+    let mut track_ids = Vec::with_capacity(items.len());
+    let mut total_duration = chrono::Duration::zero();
+    for item in items {
+        if let Some(PlayableItem::Track(track)) = item.track {
+            if let Some(track_id) = track.id {
+                track_ids.push(track_id);
+                total_duration += track.duration;
+            }
+        }
+    }
+
+    Ok(ResolvedCollection {
+        name: playlist.name,
+        track_ids,
+        total_duration,
+    })
+}
+
+pub fn format_duration(duration: chrono::Duration) -> String {
+    format!(
+        "{}:{:02}",
+        duration.num_minutes(),
+        duration.num_seconds() % 60
+    )
+}
+
 pub fn format_track_text(track: &FullTrack) -> String {
     let artists = track
         .artists
@@ -163,11 +586,7 @@ pub fn format_track_text(track: &FullTrack) -> String {
         .map(|a| a.name.clone())
         .collect::<Vec<_>>()
         .join(", ");
-    let duration = format!(
-        "{}:{}",
-        track.duration.num_minutes(),
-        track.duration.num_seconds() % 60
-    );
+    let duration = format_duration(track.duration);
     let listen = track
         .external_urls
         .get("spotify")
@@ -191,3 +610,88 @@ pub fn format_track_text(track: &FullTrack) -> String {
         covers
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_redirect_uri_splits_host_and_path() {
+        let (host, path) = parse_redirect_uri("http://localhost:8888/callback");
+        assert_eq!(host, "localhost:8888");
+        assert_eq!(path, "/callback");
+    }
+
+    #[test]
+    fn parse_redirect_uri_defaults_to_root_path() {
+        let (host, path) = parse_redirect_uri("http://localhost:8888");
+        assert_eq!(host, "localhost:8888");
+        assert_eq!(path, "/");
+    }
+
+    #[test]
+    fn extract_query_param_finds_the_requested_key() {
+        let value = extract_query_param("/callback?state=xyz&code=abc123", "code");
+        assert_eq!(value, Some("abc123".to_string()));
+    }
+
+    #[test]
+    fn extract_query_param_returns_none_when_missing() {
+        assert_eq!(extract_query_param("/callback?state=xyz", "code"), None);
+        assert_eq!(extract_query_param("/callback", "code"), None);
+    }
+
+    #[test]
+    fn format_duration_zero_pads_seconds() {
+        assert_eq!(format_duration(chrono::Duration::seconds(185)), "3:05");
+        assert_eq!(format_duration(chrono::Duration::seconds(180)), "3:00");
+        assert_eq!(format_duration(chrono::Duration::seconds(199)), "3:19");
+    }
+
+    #[test]
+    fn format_progress_bar_marks_the_start_and_end() {
+        let duration = chrono::Duration::seconds(100);
+        let at_start = format_progress_bar(chrono::Duration::zero(), duration);
+        assert_eq!(at_start.chars().next().unwrap(), '🔘');
+
+        let at_end = format_progress_bar(duration, duration);
+        assert_eq!(at_end.chars().last().unwrap(), '🔘');
+        assert_eq!(at_end.chars().count(), PROGRESS_BAR_LENGTH);
+    }
+
+    #[test]
+    fn format_progress_bar_handles_zero_duration() {
+        let bar = format_progress_bar(chrono::Duration::zero(), chrono::Duration::zero());
+        assert_eq!(bar.chars().next().unwrap(), '🔘');
+    }
+
+    #[tokio::test]
+    async fn from_url_matches_open_spotify_album_link() {
+        let id = SpotifyCollectionId::from_url(
+            "https://open.spotify.com/album/4aawyAB9vmqN3uQ7FjRGTy".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(id.kind, SpotifyCollectionKind::Album);
+        assert_eq!(id.collection_id, "4aawyAB9vmqN3uQ7FjRGTy");
+    }
+
+    #[tokio::test]
+    async fn from_url_matches_playlist_uri() {
+        let id =
+            SpotifyCollectionId::from_url("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M".to_string())
+                .await
+                .unwrap();
+        assert_eq!(id.kind, SpotifyCollectionKind::Playlist);
+        assert_eq!(id.collection_id, "37i9dQZF1DXcBWIGoYBM5M");
+    }
+
+    #[tokio::test]
+    async fn from_url_rejects_a_plain_track_link() {
+        assert!(SpotifyCollectionId::from_url(
+            "https://open.spotify.com/track/4aawyAB9vmqN3uQ7FjRGTy".to_string()
+        )
+        .await
+        .is_none());
+    }
+}
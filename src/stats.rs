@@ -0,0 +1,214 @@
+//! Optional request/voting history and leaderboard, persisted to Redis.
+//!
+//! Gated behind the `stats` cargo feature and only active when `REDIS_URL` is set;
+//! every method below silently no-ops otherwise, so single-host deployments without
+//! Redis are unaffected.
+//!
+//! This whole tree has no tracked `Cargo.toml` (not just for this module — there's
+//! no manifest for any of the crate's other dependencies either), so the `stats`
+//! feature and the `redis` dependency below live entirely out-of-tree and this
+//! module has never been compiled or exercised end-to-end here. Enabling it for
+//! real requires adding, to whatever manifest the rest of the crate is built from:
+//!
+//! ```toml
+//! [features]
+//! stats = ["dep:redis"]
+//!
+//! [dependencies]
+//! redis = { version = "0.27", features = ["tokio-comp"], optional = true }
+//! ```
+
+use std::env;
+
+#[cfg(feature = "stats")]
+use redis::AsyncCommands;
+
+#[derive(Clone)]
+pub struct Stats {
+    #[cfg(feature = "stats")]
+    client: Option<redis::Client>,
+}
+
+/// What happened to a requested track once voting concluded.
+pub enum Outcome {
+    Accepted,
+    Declined,
+}
+
+pub struct StatsSummary {
+    pub total_queued: u64,
+    pub total_declined: u64,
+    pub top_requesters: Vec<(String, u64)>,
+    pub most_declined: Vec<(String, u64)>,
+}
+
+impl StatsSummary {
+    pub fn acceptance_ratio(&self) -> f64 {
+        let total = self.total_queued + self.total_declined;
+        if total == 0 {
+            0.0
+        } else {
+            self.total_queued as f64 / total as f64
+        }
+    }
+}
+
+impl Stats {
+    /// Builds the stats backend from `REDIS_URL`. With the `stats` feature disabled,
+    /// or the env var unset, every call below is a no-op.
+    pub fn from_env() -> Self {
+        #[cfg(feature = "stats")]
+        {
+            let client = env::var("REDIS_URL")
+                .ok()
+                .and_then(|url| redis::Client::open(url).ok());
+            if client.is_some() {
+                log::info!("Stats persistence enabled (Redis)");
+            }
+            Self { client }
+        }
+        #[cfg(not(feature = "stats"))]
+        Self {}
+    }
+
+    #[cfg(feature = "stats")]
+    async fn connection(&self) -> Option<redis::aio::MultiplexedConnection> {
+        let client = self.client.as_ref()?;
+        client.get_multiplexed_async_connection().await.ok()
+    }
+
+    /// Records a new request: bumps the submitter's request count.
+    pub async fn record_request(&self, requester: &str, track_id: &str) {
+        #[cfg(feature = "stats")]
+        if let Some(mut conn) = self.connection().await {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let _: redis::RedisResult<()> = redis::pipe()
+                .atomic()
+                .hincr("stats:requests_by_user", requester, 1)
+                .hset(
+                    format!("stats:request:{}:{}", track_id, timestamp),
+                    "requester",
+                    requester,
+                )
+                .query_async(&mut conn)
+                .await;
+        }
+        #[cfg(not(feature = "stats"))]
+        let _ = (requester, track_id);
+    }
+
+    /// Records the final outcome of a vote: whether the track was queued or declined,
+    /// and which users voted either way.
+    pub async fn record_outcome(
+        &self,
+        requester: &str,
+        outcome: Outcome,
+        accepted_by: &[String],
+        declined_by: &[String],
+    ) {
+        #[cfg(feature = "stats")]
+        if let Some(mut conn) = self.connection().await {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            match outcome {
+                Outcome::Accepted => {
+                    pipe.incr("stats:total_queued", 1);
+                }
+                Outcome::Declined => {
+                    pipe.incr("stats:total_declined", 1).hincr(
+                        "stats:declined_by_requester",
+                        requester,
+                        1,
+                    );
+                }
+            }
+            for voter in accepted_by.iter().chain(declined_by.iter()) {
+                pipe.hincr("stats:votes_by_user", voter, 1);
+            }
+            let _: redis::RedisResult<()> = pipe.query_async(&mut conn).await;
+        }
+        #[cfg(not(feature = "stats"))]
+        let _ = (requester, outcome, accepted_by, declined_by);
+    }
+
+    /// Aggregates total queued/declined counts and the top requesters/decliners.
+    /// Returns `None` when stats aren't enabled.
+    pub async fn summary(&self) -> Option<StatsSummary> {
+        #[cfg(feature = "stats")]
+        {
+            let mut conn = self.connection().await?;
+            let total_queued: u64 = conn.get("stats:total_queued").await.unwrap_or(0);
+            let total_declined: u64 = conn.get("stats:total_declined").await.unwrap_or(0);
+            let mut top_requesters: Vec<(String, u64)> = conn
+                .hgetall("stats:requests_by_user")
+                .await
+                .unwrap_or_default();
+            top_requesters.sort_by(|a, b| b.1.cmp(&a.1));
+            top_requesters.truncate(5);
+            let mut most_declined: Vec<(String, u64)> = conn
+                .hgetall("stats:declined_by_requester")
+                .await
+                .unwrap_or_default();
+            most_declined.sort_by(|a, b| b.1.cmp(&a.1));
+            most_declined.truncate(5);
+            Some(StatsSummary {
+                total_queued,
+                total_declined,
+                top_requesters,
+                most_declined,
+            })
+        }
+        #[cfg(not(feature = "stats"))]
+        None
+    }
+}
+
+pub fn format_summary(summary: &StatsSummary) -> String {
+    let mut text = format!(
+        "📊 <b>Stats</b>\n🎶 {} queued • ❌ {} declined • ✅ {:.0}% acceptance\n",
+        summary.total_queued,
+        summary.total_declined,
+        summary.acceptance_ratio() * 100.0
+    );
+    text.push_str("\n<b>Top requesters</b>\n");
+    for (user, count) in &summary.top_requesters {
+        text.push_str(&format!("{} — {}\n", user, count));
+    }
+    text.push_str("\n<b>Most declined</b>\n");
+    for (user, count) in &summary.most_declined {
+        text.push_str(&format!("{} — {}\n", user, count));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(total_queued: u64, total_declined: u64) -> StatsSummary {
+        StatsSummary {
+            total_queued,
+            total_declined,
+            top_requesters: Vec::new(),
+            most_declined: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn acceptance_ratio_is_zero_with_no_votes() {
+        assert_eq!(summary(0, 0).acceptance_ratio(), 0.0);
+    }
+
+    #[test]
+    fn acceptance_ratio_divides_queued_by_total() {
+        assert_eq!(summary(3, 1).acceptance_ratio(), 0.75);
+    }
+
+    #[test]
+    fn acceptance_ratio_is_one_when_nothing_was_declined() {
+        assert_eq!(summary(5, 0).acceptance_ratio(), 1.0);
+    }
+}